@@ -1,4 +1,9 @@
+use numpy::{PyArray2, PyReadonlyArray2};
 use pyo3::prelude::*;
+use rand::rngs::StdRng;
+use rand::{thread_rng, Rng, SeedableRng};
+use rayon::prelude::*;
+use std::collections::HashMap;
 
 /// Adds two numbers together.
 pub fn add(left: u64, right: u64) -> u64 {
@@ -11,9 +16,642 @@ fn add_py(left: u64, right: u64) -> PyResult<u64> {
     Ok(add(left, right))
 }
 
+/// Precomputed Metropolis acceptance weights exp(-dE/T), indexed by spin
+/// sign and neighbor sum.
+struct AcceptanceTable {
+    /// `weights[spin_idx][neighbor_idx]`, spin_idx: 0 => spin -1, 1 => spin +1.
+    weights: [[f64; 5]; 2],
+}
+
+impl AcceptanceTable {
+    fn new(coupling: f64, field: f64, temperature: f64) -> Self {
+        let mut weights = [[0.0f64; 5]; 2];
+        for (spin_idx, &spin) in [-1i32, 1i32].iter().enumerate() {
+            for neighbor_idx in 0..5 {
+                let sum_neighbors = -4 + 2 * neighbor_idx as i32;
+                let delta_e =
+                    2.0 * spin as f64 * (coupling * sum_neighbors as f64 + field);
+                weights[spin_idx][neighbor_idx] = (-delta_e / temperature).exp();
+            }
+        }
+        AcceptanceTable { weights }
+    }
+
+    fn accept_probability(&self, spin: i8, sum_neighbors: i32) -> f64 {
+        let spin_idx = if spin > 0 { 1 } else { 0 };
+        let neighbor_idx = ((sum_neighbors + 4) / 2) as usize;
+        self.weights[spin_idx][neighbor_idx]
+    }
+}
+
+/// A square Ising lattice of +-1 spins with periodic boundaries, evolved via
+/// the Metropolis-Hastings single-spin-flip algorithm or the Wolff cluster
+/// algorithm.
+///
+/// H = -J * sum_<ij> s_i s_j - h * sum_i s_i
+#[pyclass]
+pub struct IsingLattice {
+    #[pyo3(get)]
+    size: usize,
+    #[pyo3(get)]
+    temperature: f64,
+    #[pyo3(get)]
+    coupling: f64,
+    #[pyo3(get)]
+    field: f64,
+    spins: Vec<i8>,
+    rng: StdRng,
+    table: AcceptanceTable,
+    wolff_cluster_size_total: u64,
+    wolff_cluster_count: u64,
+}
+
+#[pymethods]
+impl IsingLattice {
+    #[new]
+    #[pyo3(signature = (size, temperature, coupling=1.0, field=0.0, seed=None))]
+    fn new(
+        size: usize,
+        temperature: f64,
+        coupling: f64,
+        field: f64,
+        seed: Option<u64>,
+    ) -> PyResult<Self> {
+        if size == 0 {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "size must be positive",
+            ));
+        }
+        if temperature <= 0.0 {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "temperature must be positive",
+            ));
+        }
+
+        let mut rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        let spins = (0..size * size)
+            .map(|_| if rng.gen_bool(0.5) { 1i8 } else { -1i8 })
+            .collect();
+        let table = AcceptanceTable::new(coupling, field, temperature);
+
+        Ok(IsingLattice {
+            size,
+            temperature,
+            coupling,
+            field,
+            spins,
+            rng,
+            table,
+            wolff_cluster_size_total: 0,
+            wolff_cluster_count: 0,
+        })
+    }
+
+    /// Build an `IsingLattice` from an existing int8 spin configuration,
+    /// e.g. one crafted in NumPy to seed a domain wall.
+    #[staticmethod]
+    #[pyo3(signature = (arr, temperature, coupling=1.0, field=0.0, seed=None))]
+    fn from_numpy(
+        arr: PyReadonlyArray2<i8>,
+        temperature: f64,
+        coupling: f64,
+        field: f64,
+        seed: Option<u64>,
+    ) -> PyResult<Self> {
+        let view = arr.as_array();
+        let (rows, cols) = view.dim();
+        if rows == 0 {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "size must be positive",
+            ));
+        }
+        if rows != cols {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "lattice must be square",
+            ));
+        }
+        if view.iter().any(|&s| s != 1 && s != -1) {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "spins must be +1 or -1",
+            ));
+        }
+        if temperature <= 0.0 {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "temperature must be positive",
+            ));
+        }
+
+        let rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        let spins = view.iter().copied().collect();
+        let table = AcceptanceTable::new(coupling, field, temperature);
+
+        Ok(IsingLattice {
+            size: rows,
+            temperature,
+            coupling,
+            field,
+            spins,
+            rng,
+            table,
+            wolff_cluster_size_total: 0,
+            wolff_cluster_count: 0,
+        })
+    }
+
+    /// Return the current spin configuration as an `size` x `size` int8
+    /// NumPy array, e.g. for visualizing a snapshot with matplotlib.
+    fn to_numpy<'py>(&self, py: Python<'py>) -> Bound<'py, PyArray2<i8>> {
+        let rows: Vec<Vec<i8>> = self
+            .spins
+            .chunks(self.size)
+            .map(|row| row.to_vec())
+            .collect();
+        PyArray2::from_vec2_bound(py, &rows).expect("row lengths are all `size`")
+    }
+
+    /// Indices of the four periodic-boundary neighbors of site `idx`.
+    fn neighbor_indices(&self, idx: usize) -> [usize; 4] {
+        let l = self.size;
+        let row = idx / l;
+        let col = idx % l;
+        [
+            ((row + l - 1) % l) * l + col,
+            ((row + 1) % l) * l + col,
+            row * l + (col + l - 1) % l,
+            row * l + (col + 1) % l,
+        ]
+    }
+
+    /// Sum of the four periodic-boundary neighbor spins of site `idx`.
+    fn neighbor_sum(&self, idx: usize) -> i32 {
+        self.neighbor_indices(idx)
+            .iter()
+            .map(|&n| self.spins[n] as i32)
+            .sum()
+    }
+
+    /// Attempt one Metropolis spin flip at a uniformly chosen site.
+    fn attempt_flip(&mut self) {
+        let idx = self.rng.gen_range(0..self.spins.len());
+        let spin = self.spins[idx];
+        let sum_neighbors = self.neighbor_sum(idx);
+        let delta_e = 2.0 * spin as f64 * (self.coupling * sum_neighbors as f64 + self.field);
+
+        let accept = delta_e <= 0.0
+            || self.rng.gen::<f64>() < self.table.accept_probability(spin, sum_neighbors);
+        if accept {
+            self.spins[idx] = -spin;
+        }
+    }
+
+    /// Run `n` sweeps, where one sweep is size^2 attempted single-spin flips.
+    fn sweep(&mut self, n: usize) {
+        let flips_per_sweep = self.spins.len();
+        for _ in 0..n * flips_per_sweep {
+            self.attempt_flip();
+        }
+    }
+
+    /// Grow and flip one Wolff cluster from a random seed site, returning the
+    /// cluster size. Errs if `field != 0.0`, since the plain bond-percolation
+    /// cluster move has no ghost-spin correction for an external field and
+    /// would silently break detailed balance.
+    fn wolff_step(&mut self) -> PyResult<usize> {
+        if self.field != 0.0 {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "wolff_step requires field == 0.0; use sweep() for nonzero field",
+            ));
+        }
+
+        let n = self.spins.len();
+        let mut in_cluster = vec![false; n];
+        let seed = self.rng.gen_range(0..n);
+        let cluster_spin = self.spins[seed];
+        let bond_probability = 1.0 - (-2.0 * self.coupling / self.temperature).exp();
+
+        let mut stack = vec![seed];
+        in_cluster[seed] = true;
+        let mut cluster = vec![seed];
+
+        while let Some(site) = stack.pop() {
+            for neighbor in self.neighbor_indices(site) {
+                if !in_cluster[neighbor]
+                    && self.spins[neighbor] == cluster_spin
+                    && self.rng.gen::<f64>() < bond_probability
+                {
+                    in_cluster[neighbor] = true;
+                    stack.push(neighbor);
+                    cluster.push(neighbor);
+                }
+            }
+        }
+
+        for &site in &cluster {
+            self.spins[site] = -cluster_spin;
+        }
+        self.wolff_cluster_size_total += cluster.len() as u64;
+        self.wolff_cluster_count += 1;
+        Ok(cluster.len())
+    }
+
+    /// Mean cluster size over all `wolff_step` calls made so far.
+    fn mean_cluster_size(&self) -> f64 {
+        if self.wolff_cluster_count == 0 {
+            return 0.0;
+        }
+        self.wolff_cluster_size_total as f64 / self.wolff_cluster_count as f64
+    }
+
+    /// Total energy H of the current configuration (not per-spin).
+    fn energy(&self) -> f64 {
+        let mut total = 0.0;
+        for idx in 0..self.spins.len() {
+            let spin = self.spins[idx] as f64;
+            // Only count each bond once by looking at the "right" and "down" neighbor.
+            let l = self.size;
+            let row = idx / l;
+            let col = idx % l;
+            let right = row * l + (col + 1) % l;
+            let down = ((row + 1) % l) * l + col;
+            total -= self.coupling * spin * self.spins[right] as f64;
+            total -= self.coupling * spin * self.spins[down] as f64;
+            total -= self.field * spin;
+        }
+        total
+    }
+
+    /// Total magnetization (sum of spins, not per-spin).
+    fn magnetization(&self) -> f64 {
+        self.spins.iter().map(|&s| s as f64).sum()
+    }
+
+    /// Equilibrate for `equilibration` sweeps, then sample every
+    /// `measure_every` sweeps up to `sweeps` total, returning energy/spin,
+    /// |M|/spin, susceptibility, and specific heat.
+    #[pyo3(signature = (sweeps, equilibration=0, measure_every=1))]
+    fn run(
+        &mut self,
+        sweeps: usize,
+        equilibration: usize,
+        measure_every: usize,
+    ) -> PyResult<HashMap<String, f64>> {
+        if measure_every == 0 {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "measure_every must be positive",
+            ));
+        }
+
+        let n = self.spins.len() as f64;
+        let mut sum_e = 0.0;
+        let mut sum_e2 = 0.0;
+        let mut sum_m = 0.0;
+        let mut sum_m2 = 0.0;
+        let mut samples = 0u64;
+
+        for step in 0..sweeps {
+            self.sweep(1);
+            if step >= equilibration && (step - equilibration) % measure_every == 0 {
+                let e = self.energy();
+                let m = self.magnetization().abs();
+                sum_e += e;
+                sum_e2 += e * e;
+                sum_m += m;
+                sum_m2 += m * m;
+                samples += 1;
+            }
+        }
+
+        let mut observables = HashMap::new();
+        if samples == 0 {
+            observables.insert("energy_per_spin".to_string(), f64::NAN);
+            observables.insert("abs_magnetization_per_spin".to_string(), f64::NAN);
+            observables.insert("susceptibility".to_string(), f64::NAN);
+            observables.insert("specific_heat".to_string(), f64::NAN);
+            return Ok(observables);
+        }
+
+        let samples = samples as f64;
+        let mean_e = sum_e / samples;
+        let mean_e2 = sum_e2 / samples;
+        let mean_m = sum_m / samples;
+        let mean_m2 = sum_m2 / samples;
+
+        observables.insert("energy_per_spin".to_string(), mean_e / n);
+        observables.insert("abs_magnetization_per_spin".to_string(), mean_m / n);
+        observables.insert(
+            "susceptibility".to_string(),
+            (mean_m2 - mean_m * mean_m) / (n * self.temperature),
+        );
+        observables.insert(
+            "specific_heat".to_string(),
+            (mean_e2 - mean_e * mean_e) / (n * self.temperature * self.temperature),
+        );
+        Ok(observables)
+    }
+}
+
+/// Replica-exchange (parallel tempering) across an ascending temperature
+/// ladder of `IsingLattice`s.
+#[pyclass]
+pub struct ParallelTempering {
+    replicas: Vec<IsingLattice>,
+    swap_attempts: Vec<u64>,
+    swap_accepts: Vec<u64>,
+    rng: StdRng,
+    even_parity: bool,
+}
+
+#[pymethods]
+impl ParallelTempering {
+    #[new]
+    #[pyo3(signature = (size, temperatures, coupling=1.0, field=0.0, seed=None))]
+    fn new(
+        size: usize,
+        temperatures: Vec<f64>,
+        coupling: f64,
+        field: f64,
+        seed: Option<u64>,
+    ) -> PyResult<Self> {
+        if temperatures.len() < 2 {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "need at least two temperatures to exchange replicas",
+            ));
+        }
+        if temperatures.windows(2).any(|w| w[0] >= w[1]) {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "temperatures must be strictly ascending",
+            ));
+        }
+
+        let base_seed = seed.unwrap_or_else(|| thread_rng().gen());
+        let replicas = temperatures
+            .iter()
+            .enumerate()
+            .map(|(i, &t)| {
+                IsingLattice::new(
+                    size,
+                    t,
+                    coupling,
+                    field,
+                    Some(base_seed.wrapping_add(i as u64)),
+                )
+            })
+            .collect::<PyResult<Vec<_>>>()?;
+
+        let n_pairs = replicas.len() - 1;
+        Ok(ParallelTempering {
+            replicas,
+            swap_attempts: vec![0; n_pairs],
+            swap_accepts: vec![0; n_pairs],
+            rng: StdRng::seed_from_u64(base_seed),
+            even_parity: true,
+        })
+    }
+
+    /// Run `sweeps` Metropolis sweeps and `wolff_sweeps` Wolff cluster
+    /// updates on every replica (Wolff requires `field == 0.0`), then
+    /// propose swaps on the current even or odd set of adjacent pairs.
+    #[pyo3(signature = (sweeps, wolff_sweeps=0))]
+    fn step(&mut self, sweeps: usize, wolff_sweeps: usize) -> PyResult<()> {
+        for replica in &mut self.replicas {
+            replica.sweep(sweeps);
+            for _ in 0..wolff_sweeps {
+                replica.wolff_step()?;
+            }
+        }
+
+        let start = if self.even_parity { 0 } else { 1 };
+        let mut i = start;
+        while i + 1 < self.replicas.len() {
+            self.try_swap(i);
+            i += 2;
+        }
+        self.even_parity = !self.even_parity;
+        Ok(())
+    }
+
+    /// Run `IsingLattice.run` independently on each replica.
+    fn run(
+        &mut self,
+        sweeps: usize,
+        equilibration: usize,
+        measure_every: usize,
+    ) -> PyResult<Vec<HashMap<String, f64>>> {
+        self.replicas
+            .iter_mut()
+            .map(|replica| replica.run(sweeps, equilibration, measure_every))
+            .collect()
+    }
+
+    /// Acceptance rate of proposed swaps for each adjacent pair (i, i+1).
+    fn swap_acceptance_rates(&self) -> Vec<f64> {
+        self.swap_attempts
+            .iter()
+            .zip(&self.swap_accepts)
+            .map(|(&attempts, &accepts)| {
+                if attempts == 0 {
+                    0.0
+                } else {
+                    accepts as f64 / attempts as f64
+                }
+            })
+            .collect()
+    }
+
+    /// The temperature ladder, ascending.
+    fn temperatures(&self) -> Vec<f64> {
+        self.replicas.iter().map(|r| r.temperature).collect()
+    }
+
+    /// Total energy of each replica at its current configuration.
+    fn energies(&self) -> Vec<f64> {
+        self.replicas.iter().map(|r| r.energy()).collect()
+    }
+}
+
+impl ParallelTempering {
+    /// Propose a swap between replicas `i` and `i + 1`.
+    fn try_swap(&mut self, i: usize) {
+        let beta_i = 1.0 / self.replicas[i].temperature;
+        let beta_j = 1.0 / self.replicas[i + 1].temperature;
+        let e_i = self.replicas[i].energy();
+        let e_j = self.replicas[i + 1].energy();
+
+        let delta = (beta_i - beta_j) * (e_i - e_j);
+        let accept = delta >= 0.0 || self.rng.gen::<f64>() < delta.exp();
+
+        self.swap_attempts[i] += 1;
+        if accept {
+            let (left, right) = self.replicas.split_at_mut(i + 1);
+            std::mem::swap(&mut left[i].spins, &mut right[0].spins);
+            self.swap_accepts[i] += 1;
+        }
+    }
+}
+
+/// Run one independent simulation per temperature in parallel via Rayon,
+/// releasing the GIL.
+#[pyfunction]
+#[pyo3(signature = (size, temperatures, sweeps, equilibration=0, measure_every=1, coupling=1.0, field=0.0, seed=None))]
+fn ensemble_run(
+    py: Python<'_>,
+    size: usize,
+    temperatures: Vec<f64>,
+    sweeps: usize,
+    equilibration: usize,
+    measure_every: usize,
+    coupling: f64,
+    field: f64,
+    seed: Option<u64>,
+) -> PyResult<Vec<HashMap<String, f64>>> {
+    let base_seed = seed.unwrap_or_else(|| thread_rng().gen());
+
+    py.allow_threads(|| {
+        temperatures
+            .par_iter()
+            .enumerate()
+            .map(|(i, &temperature)| {
+                let mut lattice = IsingLattice::new(
+                    size,
+                    temperature,
+                    coupling,
+                    field,
+                    Some(base_seed.wrapping_add(i as u64)),
+                )?;
+                lattice.run(sweeps, equilibration, measure_every)
+            })
+            .collect()
+    })
+}
+
 /// A Python module implemented in Rust.
 #[pymodule]
 fn rust_core(m: &Bound<'_, PyModule>) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(add_py, m)?)?;
+    m.add_function(wrap_pyfunction!(ensemble_run, m)?)?;
+    m.add_class::<IsingLattice>()?;
+    m.add_class::<ParallelTempering>()?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use numpy::PyArray2;
+
+    /// A 2x2 checkerboard is the antiferromagnetic-like configuration that
+    /// maximizes H for J=1, h=0: all eight bond terms (each site's right and
+    /// down bond; on a 2x2 periodic lattice the wrap counts each neighbor pair
+    /// twice) are unsatisfied (+1/-1 neighbors), giving H = 8 and zero net
+    /// magnetization.
+    #[test]
+    fn energy_and_magnetization_on_checkerboard() {
+        let mut lattice = IsingLattice::new(2, 1.0, 1.0, 0.0, Some(0)).unwrap();
+        lattice.spins = vec![1, -1, -1, 1];
+
+        assert_eq!(lattice.energy(), 8.0);
+        assert_eq!(lattice.magnetization(), 0.0);
+    }
+
+    #[test]
+    fn acceptance_table_approaches_one_at_high_temperature() {
+        let table = AcceptanceTable::new(1.0, 0.0, 1e6);
+        for spin_idx in 0..2 {
+            for neighbor_idx in 0..5 {
+                assert!((table.weights[spin_idx][neighbor_idx] - 1.0).abs() < 1e-3);
+            }
+        }
+    }
+
+    /// At low temperature the bond probability 1 - exp(-2J/T) is ~1, so a
+    /// uniform-spin lattice's cluster should engulf every site and flip it.
+    #[test]
+    fn wolff_step_engulfs_uniform_lattice_at_low_temperature() {
+        let mut lattice = IsingLattice::new(4, 0.01, 1.0, 0.0, Some(1)).unwrap();
+        lattice.spins = vec![1; 16];
+
+        let cluster_size = lattice.wolff_step().unwrap();
+
+        assert_eq!(cluster_size, 16);
+        assert!(lattice.spins.iter().all(|&s| s == -1));
+    }
+
+    #[test]
+    fn wolff_step_rejects_nonzero_field() {
+        let mut lattice = IsingLattice::new(4, 1.0, 1.0, 0.5, Some(1)).unwrap();
+        assert!(lattice.wolff_step().is_err());
+    }
+
+    /// At T=0.001 the uniform ground state's flip acceptance underflows to
+    /// exactly 0, so the lattice never changes across the run: every sample
+    /// is identical, giving zero variance and hence chi = C = 0 exactly.
+    #[test]
+    fn run_moments_are_exact_for_a_frozen_low_temperature_lattice() {
+        let mut lattice = IsingLattice::new(2, 0.001, 1.0, 0.0, Some(2)).unwrap();
+        lattice.spins = vec![1, 1, 1, 1];
+
+        let observables = lattice.run(20, 0, 1).unwrap();
+
+        assert_eq!(observables["energy_per_spin"], -2.0);
+        assert_eq!(observables["abs_magnetization_per_spin"], 1.0);
+        assert_eq!(observables["susceptibility"], 0.0);
+        assert_eq!(observables["specific_heat"], 0.0);
+    }
+
+    #[test]
+    fn from_numpy_to_numpy_round_trips() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let data = vec![vec![1i8, -1], vec![-1, 1]];
+            let arr = PyArray2::from_vec2_bound(py, &data).unwrap();
+            let lattice =
+                IsingLattice::from_numpy(arr.readonly(), 1.0, 1.0, 0.0, Some(0)).unwrap();
+            let round_tripped = lattice.to_numpy(py).readonly().to_vec2().unwrap();
+            assert_eq!(round_tripped, data);
+        });
+    }
+
+    #[test]
+    fn parallel_tempering_rejects_fewer_than_two_temperatures() {
+        assert!(ParallelTempering::new(4, vec![1.0], 1.0, 0.0, Some(0)).is_err());
+    }
+
+    #[test]
+    fn parallel_tempering_rejects_non_ascending_temperatures() {
+        assert!(ParallelTempering::new(4, vec![2.0, 1.0], 1.0, 0.0, Some(0)).is_err());
+    }
+
+    /// When adjacent replicas sit at (near-)equal temperatures, beta_i - beta_j
+    /// is ~0, so exp((beta_i - beta_j) * (E_i - E_j)) is ~1 and swaps should
+    /// be accepted almost every time regardless of the energy difference.
+    #[test]
+    fn swap_acceptance_is_high_for_near_degenerate_replicas() {
+        let mut pt = ParallelTempering::new(4, vec![1.0, 1.0 + 1e-9], 1.0, 0.0, Some(3)).unwrap();
+        for _ in 0..20 {
+            pt.step(2, 0).unwrap();
+        }
+
+        let rates = pt.swap_acceptance_rates();
+        assert_eq!(rates.len(), 1);
+        assert!(rates[0] > 0.9);
+    }
+
+    #[test]
+    fn ensemble_run_is_deterministic_for_a_fixed_seed() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let temperatures = vec![1.5, 2.0, 2.5];
+            let first = ensemble_run(py, 4, temperatures.clone(), 50, 10, 1, 1.0, 0.0, Some(7))
+                .unwrap();
+            let second =
+                ensemble_run(py, 4, temperatures, 50, 10, 1, 1.0, 0.0, Some(7)).unwrap();
+            assert_eq!(first, second);
+        });
+    }
+}